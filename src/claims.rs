@@ -1,5 +1,5 @@
 use std::collections::BTreeMap;
-use rustc_serialize::Decodable;
+use rustc_serialize::{Decodable, Encodable};
 use rustc_serialize::base64::{
     FromBase64,
     ToBase64,
@@ -8,78 +8,340 @@ use rustc_serialize::json::{
     self,
     Decoder,
     Json,
+    ToJson,
 };
 use Component;
 use error::Error;
 use BASE_CONFIG;
 
+/// JWT Claims, parameterized over the type of the private claims. `T`
+/// defaults to a `BTreeMap<String, Json>` so a bare `Claims` type still names
+/// an untyped bag of private claims (calls such as `Claims::from_base64` need
+/// that type written out, since defaults don't drive inference), but callers
+/// can substitute
+/// their own `RustcDecodable`/`RustcEncodable` struct to get compile-time
+/// checked access to their application's claims.
 #[derive(Debug, Default, PartialEq)]
-pub struct Claims {
+pub struct Claims<T = BTreeMap<String, Json>> {
     pub reg: Registered,
-    pub private: BTreeMap<String, Json>,
+    pub private: T,
 }
 
 #[derive(Debug, Default, PartialEq, RustcDecodable, RustcEncodable)]
 pub struct Registered {
     pub iss: Option<String>,
     pub sub: Option<String>,
-    pub aud: Option<String>,
+    pub aud: Option<Audience>,
     pub exp: Option<u64>,
     pub nbf: Option<u64>,
     pub iat: Option<u64>,
     pub jti: Option<String>,
 }
 
-/// JWT Claims. Registered claims are directly accessible via the `Registered`
-/// struct embedded, while private fields are a map that contains `Json`
-/// values.
-impl Claims {
-    pub fn new(reg: Registered) -> Claims {
+impl Registered {
+    /// Returns whether `aud` is the single audience, or one of the
+    /// audiences, named in the `aud` claim, regardless of which of the two
+    /// RFC 7519 representations it was encoded in.
+    pub fn has_audience(&self, aud: &str) -> bool {
+        match self.aud {
+            Some(Audience::Single(ref a)) => a == aud,
+            Some(Audience::Multiple(ref aud_list)) => aud_list.iter().any(|a| a == aud),
+            None => false,
+        }
+    }
+}
+
+/// The `aud` (audience) claim. RFC 7519 allows this to be encoded as either
+/// a single string or an array of strings; this preserves whichever shape
+/// the token was decoded from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    /// Builds an `Audience` from an already-parsed `Json` value, accepting
+    /// either RFC 7519 representation.
+    fn from_json(value: &Json) -> Result<Audience, Error> {
+        match *value {
+            Json::String(ref s) => Ok(Audience::Single(s.clone())),
+            Json::Array(ref items) => {
+                let mut aud = Vec::with_capacity(items.len());
+                for item in items {
+                    match *item {
+                        Json::String(ref s) => aud.push(s.clone()),
+                        _ => return Err(Error::Format),
+                    }
+                }
+                Ok(Audience::Multiple(aud))
+            }
+            _ => Err(Error::Format),
+        }
+    }
+}
+
+impl Decodable for Audience {
+    // `rustc_serialize`'s `Decoder` has no way to inspect a value's JSON
+    // type without consuming it, so a generic decode can't try the string
+    // form and safely fall back to the array form. This only supports the
+    // single-string form; `Claims::from_base64` decodes `aud` itself by
+    // matching on the parsed `Json` value via `Audience::from_json`, which
+    // handles both forms. Decoding a `Registered` on its own (e.g.
+    // `Registered::from_base64`) goes through this impl, so there an array
+    // `aud` is rejected.
+    fn decode<D: ::rustc_serialize::Decoder>(d: &mut D) -> Result<Audience, D::Error> {
+        d.read_str().map(Audience::Single)
+    }
+}
+
+impl Encodable for Audience {
+    fn encode<S: ::rustc_serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        match *self {
+            Audience::Single(ref aud) => s.emit_str(aud),
+            Audience::Multiple(ref aud_list) => {
+                s.emit_seq(aud_list.len(), |s| {
+                    for (i, aud) in aud_list.iter().enumerate() {
+                        try!(s.emit_seq_elt(i, |s| s.emit_str(aud)));
+                    }
+                    Ok(())
+                })
+            }
+        }
+    }
+}
+
+impl ToJson for Audience {
+    fn to_json(&self) -> Json {
+        match *self {
+            Audience::Single(ref aud) => aud.to_json(),
+            Audience::Multiple(ref aud_list) => aud_list.to_json(),
+        }
+    }
+}
+
+impl<T: Default> Claims<T> {
+    pub fn new(reg: Registered) -> Claims<T> {
         Claims {
             reg: reg,
-            private: BTreeMap::new(),
+            private: Default::default(),
+        }
+    }
+}
+
+impl<T> Claims<T> {
+    /// Checks the registered temporal and identity claims against `now` and
+    /// `opts`. `now` is taken as a parameter, rather than read from the
+    /// system clock, so that this crate's core stays dependency-free; see
+    /// `now()` for a convenience helper that sources it for you.
+    pub fn validate(&self, now: u64, opts: &Validation) -> Result<(), Error> {
+        if opts.validate_exp {
+            if let Some(exp) = self.reg.exp {
+                if now > exp.saturating_add(opts.leeway) {
+                    return Err(Error::ExpiredSignature);
+                }
+            }
+        }
+
+        if opts.validate_nbf {
+            if let Some(nbf) = self.reg.nbf {
+                if now.saturating_add(opts.leeway) < nbf {
+                    return Err(Error::ImmatureSignature);
+                }
+            }
+        }
+
+        if opts.validate_iat {
+            if let Some(iat) = self.reg.iat {
+                if iat > now.saturating_add(opts.leeway) {
+                    return Err(Error::InvalidIssuedAt);
+                }
+            }
+        }
+
+        if let Some(ref expected) = opts.expected_iss {
+            if self.reg.iss.as_ref() != Some(expected) {
+                return Err(Error::InvalidIssuer);
+            }
+        }
+
+        if let Some(ref expected) = opts.expected_aud {
+            if !self.reg.has_audience(expected) {
+                return Err(Error::InvalidAudience);
+            }
+        }
+
+        if let Some(ref expected) = opts.expected_sub {
+            if self.reg.sub.as_ref() != Some(expected) {
+                return Err(Error::InvalidSubject);
+            }
         }
+
+        Ok(())
     }
 }
 
-impl Component for Claims {
-    fn from_base64(raw: &str) -> Result<Claims, Error> {
+/// Options controlling `Claims::validate`. `leeway` accounts for clock skew
+/// between the issuer and the verifier when checking `exp`/`nbf`/`iat`.
+#[derive(Debug)]
+pub struct Validation {
+    pub leeway: u64,
+    pub validate_exp: bool,
+    pub validate_nbf: bool,
+    pub validate_iat: bool,
+    pub expected_iss: Option<String>,
+    pub expected_aud: Option<String>,
+    pub expected_sub: Option<String>,
+}
+
+impl Default for Validation {
+    fn default() -> Validation {
+        Validation {
+            leeway: 0,
+            validate_exp: true,
+            validate_nbf: true,
+            validate_iat: true,
+            expected_iss: None,
+            expected_aud: None,
+            expected_sub: None,
+        }
+    }
+}
+
+/// Returns the current Unix timestamp, for callers of `Claims::validate`
+/// who don't want to track time themselves.
+pub fn now() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Inserts each named `Option` field of `$reg` into `$tree` only when it is
+/// `Some`, so unset registered claims are omitted rather than encoded as
+/// `null`.
+macro_rules! insert_present_fields {
+    ($tree:expr, $reg:expr, $($field:ident),*) => {
+        $(
+            if let Some(ref v) = $reg.$field {
+                $tree.insert(stringify!($field).to_owned(), v.to_json());
+            }
+        )*
+    };
+}
+
+/// The registered claim names that aren't `aud`. `aud` is decoded
+/// separately via `Audience::from_json`, since it isn't safe to decode
+/// generically (see `impl Decodable for Audience`).
+const NON_AUDIENCE_FIELDS: [&'static str; 6] = [
+    "iss", "sub",
+    "exp", "nbf", "iat",
+    "jti",
+];
+
+/// Decodes the registered claims, including `aud`, out of `obj` without
+/// mutating it.
+fn decode_registered(obj: &BTreeMap<String, Json>) -> Result<Registered, Error> {
+    let aud = match obj.get("aud") {
+        Some(v) => Some(try!(Audience::from_json(v))),
+        None => None,
+    };
+
+    let reg_tree: BTreeMap<_, _> = NON_AUDIENCE_FIELDS.iter()
+        .filter_map(|f| obj.get(*f).map(|v| (f.to_string(), v.clone())))
+        .collect();
+
+    let mut decoder = Decoder::new(Json::Object(reg_tree));
+    let mut reg_claims: Registered = try!(Decodable::decode(&mut decoder));
+    reg_claims.aud = aud;
+    Ok(reg_claims)
+}
+
+/// The default, untyped private claims. This has its own `Component` impl,
+/// rather than going through the generic one below, because `Json` isn't
+/// `Decodable`: on decode, a `BTreeMap<String, Json>` can only be built by
+/// moving entries straight out of the already-parsed claim set, not by
+/// decoding it. Encoding still clones `self.private`, since `to_base64`
+/// only has `&self` to work with.
+impl Component for Claims<BTreeMap<String, Json>> {
+    fn from_base64(raw: &str) -> Result<Claims<BTreeMap<String, Json>>, Error> {
         let data = try!(raw.from_base64());
         let s = try!(String::from_utf8(data));
-        let tree = match try!(Json::from_str(&*s)) {
+        let mut tree = match try!(Json::from_str(&*s)) {
             Json::Object(x) => x,
             _ => return Err(Error::Format),
         };
 
-        const FIELDS: [&'static str; 7] = [
-            "iss", "sub", "aud",
-            "exp", "nbf", "iat",
-            "jti",
-        ];
+        let reg_claims = try!(decode_registered(&tree));
+        tree.remove("aud");
+        for field in NON_AUDIENCE_FIELDS.iter() {
+            tree.remove(*field);
+        }
+
+        // Whatever is left in `tree` after removing the registered fields
+        // is exactly the private claims, moved in directly with no decode
+        // or re-parse.
+        Ok(Claims{
+            reg: reg_claims,
+            private: tree,
+        })
+    }
+
+    fn to_base64(&self) -> Result<String, Error> {
+        let mut tree = BTreeMap::new();
+        insert_present_fields!(tree, self.reg, iss, sub, aud, exp, nbf, iat, jti);
+
+        // No decode/encode round trip here, but `self.private` is still
+        // cloned: `&self` doesn't let us move out of it.
+        tree.extend(self.private.clone());
+
+        let s = try!(json::encode(&tree));
+        let enc = (&*s).as_bytes().to_base64(BASE_CONFIG);
+        Ok(enc)
+    }
+}
+
+/// Private claims typed as a user-defined `RustcDecodable`/`RustcEncodable`
+/// struct. Unlike the default map above, these don't come pre-parsed as
+/// `Json`, so they still go through one decode/encode pass of their own.
+impl<T: Decodable + Encodable> Component for Claims<T> {
+    fn from_base64(raw: &str) -> Result<Claims<T>, Error> {
+        let data = try!(raw.from_base64());
+        let s = try!(String::from_utf8(data));
+        let json = try!(Json::from_str(&*s));
 
-        let (reg, pri): (BTreeMap<_, _>, BTreeMap<_, _>) = tree.into_iter()
-            .partition(|&(ref key, _)| {
-                FIELDS.iter().any(|f| f == key)
-            });
+        let reg_claims = match json {
+            Json::Object(ref obj) => try!(decode_registered(obj)),
+            _ => return Err(Error::Format),
+        };
 
-        let mut decoder = Decoder::new(Json::Object(reg));
-        let reg_claims: Registered = try!(Decodable::decode(&mut decoder));
+        // `json` is moved here rather than re-parsed, so there is only ever
+        // one parse of the raw token for both the registered and private
+        // claims.
+        let mut decoder = Decoder::new(json);
+        let private: T = try!(Decodable::decode(&mut decoder));
 
         Ok(Claims{
             reg: reg_claims,
-            private: pri,
+            private: private,
         })
     }
 
     fn to_base64(&self) -> Result<String, Error> {
-        // Extremely inefficient
-        let s = try!(json::encode(&self.reg));
-        let mut tree = match try!(Json::from_str(&*s)) {
+        let mut tree = BTreeMap::new();
+        insert_present_fields!(tree, self.reg, iss, sub, aud, exp, nbf, iat, jti);
+
+        // `self.private` isn't `Json` already, so it still needs its own
+        // encode/parse pass; its members are then moved (not cloned) into
+        // `tree` before the single final encode.
+        let p = try!(json::encode(&self.private));
+        let private_tree = match try!(Json::from_str(&*p)) {
             Json::Object(x) => x,
             _ => return Err(Error::Format),
         };
-
-        tree.extend(self.private.clone());
+        tree.extend(private_tree);
 
         let s = try!(json::encode(&tree));
         let enc = (&*s).as_bytes().to_base64(BASE_CONFIG);
@@ -90,13 +352,16 @@ impl Component for Claims {
 #[cfg(test)]
 mod tests {
     use std::default::Default;
-    use claims::{Claims, Registered};
+    use rustc_serialize::base64::FromBase64;
+    use rustc_serialize::json::Json;
+    use claims::{Audience, Claims, Registered, Validation};
+    use error::Error;
     use Component;
 
     #[test]
     fn from_base64() {
         let enc = "ew0KICAiaXNzIjogIm1pa2t5YW5nLmNvbSIsDQogICJleHAiOiAxMzAyMzE5MTAwLA0KICAibmFtZSI6ICJNaWNoYWVsIFlhbmciLA0KICAiYWRtaW4iOiB0cnVlDQp9";
-        let claims = Claims::from_base64(enc).unwrap();
+        let claims: Claims = Claims::from_base64(enc).unwrap();
 
         assert_eq!(claims.reg.iss.unwrap(), "mikkyang.com");
         assert_eq!(claims.reg.exp.unwrap(), 1302319100);
@@ -119,4 +384,137 @@ mod tests {
         let enc = claims.to_base64().unwrap();
         assert_eq!(claims, Claims::from_base64(&*enc).unwrap());
     }
+
+    #[test]
+    fn to_base64_omits_unset_registered_claims() {
+        let mut claims: Claims = Default::default();
+        claims.reg.iss = Some("mikkyang.com".into());
+        claims.reg.exp = Some(1302319100);
+
+        let enc = claims.to_base64().unwrap();
+        let data = enc.as_bytes().from_base64().unwrap();
+        let s = String::from_utf8(data).unwrap();
+        let tree = match Json::from_str(&*s).unwrap() {
+            Json::Object(x) => x,
+            _ => panic!("expected an object"),
+        };
+
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.values().any(|v| *v == Json::Null));
+    }
+
+    #[test]
+    fn validate_expired() {
+        let mut claims: Claims = Default::default();
+        claims.reg.exp = Some(100);
+
+        assert_eq!(
+            claims.validate(200, &Validation::default()),
+            Err(Error::ExpiredSignature)
+        );
+        assert_eq!(
+            claims.validate(100, &Validation::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validate_immature() {
+        let mut claims: Claims = Default::default();
+        claims.reg.nbf = Some(100);
+
+        assert_eq!(
+            claims.validate(50, &Validation::default()),
+            Err(Error::ImmatureSignature)
+        );
+        assert_eq!(
+            claims.validate(100, &Validation::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validate_leeway() {
+        let mut claims: Claims = Default::default();
+        claims.reg.exp = Some(100);
+
+        let opts = Validation { leeway: 10, ..Default::default() };
+        assert_eq!(claims.validate(105, &opts), Ok(()));
+        assert_eq!(claims.validate(111, &opts), Err(Error::ExpiredSignature));
+    }
+
+    #[test]
+    fn validate_leeway_does_not_overflow() {
+        let mut claims: Claims = Default::default();
+        claims.reg.exp = Some(u64::max_value() - 1);
+
+        let opts = Validation { leeway: 10, ..Default::default() };
+        assert_eq!(claims.validate(u64::max_value(), &opts), Ok(()));
+    }
+
+    #[test]
+    fn validate_issued_at() {
+        let mut claims: Claims = Default::default();
+        claims.reg.iat = Some(100);
+
+        assert_eq!(
+            claims.validate(50, &Validation::default()),
+            Err(Error::InvalidIssuedAt)
+        );
+        assert_eq!(
+            claims.validate(100, &Validation::default()),
+            Ok(())
+        );
+
+        let opts = Validation { leeway: 10, ..Default::default() };
+        assert_eq!(claims.validate(95, &opts), Ok(()));
+        assert_eq!(claims.validate(89, &opts), Err(Error::InvalidIssuedAt));
+    }
+
+    #[test]
+    fn validate_issuer() {
+        let mut claims: Claims = Default::default();
+        claims.reg.iss = Some("mikkyang.com".into());
+
+        let opts = Validation {
+            expected_iss: Some("someone.else".into()),
+            ..Default::default()
+        };
+        assert_eq!(claims.validate(0, &opts), Err(Error::InvalidIssuer));
+
+        let opts = Validation {
+            expected_iss: Some("mikkyang.com".into()),
+            ..Default::default()
+        };
+        assert_eq!(claims.validate(0, &opts), Ok(()));
+    }
+
+    #[test]
+    fn audience_single_roundtrip() {
+        let mut claims: Claims = Default::default();
+        claims.reg.aud = Some(Audience::Single("client-1".into()));
+
+        let enc = claims.to_base64().unwrap();
+        let decoded: Claims = Claims::from_base64(&*enc).unwrap();
+        assert_eq!(decoded.reg.aud, Some(Audience::Single("client-1".into())));
+        assert!(decoded.reg.has_audience("client-1"));
+        assert!(!decoded.reg.has_audience("client-2"));
+    }
+
+    #[test]
+    fn audience_multiple_roundtrip() {
+        let mut claims: Claims = Default::default();
+        claims.reg.aud = Some(Audience::Multiple(
+            vec!["client-1".into(), "client-2".into()]
+        ));
+
+        let enc = claims.to_base64().unwrap();
+        let decoded: Claims = Claims::from_base64(&*enc).unwrap();
+        assert_eq!(decoded.reg.aud, Some(Audience::Multiple(
+            vec!["client-1".into(), "client-2".into()]
+        )));
+        assert!(decoded.reg.has_audience("client-1"));
+        assert!(decoded.reg.has_audience("client-2"));
+        assert!(!decoded.reg.has_audience("client-3"));
+    }
 }
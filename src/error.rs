@@ -0,0 +1,111 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::string::FromUtf8Error;
+
+use rustc_serialize::base64::FromBase64Error;
+use rustc_serialize::json::{
+    DecoderError,
+    EncoderError,
+    ParserError,
+};
+
+/// Errors that can occur while encoding, decoding, or validating a JWT.
+#[derive(Debug)]
+pub enum Error {
+    /// The token's body (or, on encode, the private claims) was not a JSON
+    /// object, or its `aud` claim was neither a string nor an array of
+    /// strings.
+    Format,
+    Utf8(FromUtf8Error),
+    Json(ParserError),
+    Base64(FromBase64Error),
+    JsonDecode(DecoderError),
+    JsonEncode(EncoderError),
+
+    /// `exp` is in the past, beyond the configured leeway.
+    ExpiredSignature,
+    /// `nbf` is in the future, beyond the configured leeway.
+    ImmatureSignature,
+    /// `iat` is in the future, beyond the configured leeway.
+    InvalidIssuedAt,
+    /// `iss` doesn't match the expected issuer.
+    InvalidIssuer,
+    /// `aud` doesn't contain the expected audience.
+    InvalidAudience,
+    /// `sub` doesn't match the expected subject.
+    InvalidSubject,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", StdError::description(self))
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Format => "invalid format",
+            Error::Utf8(ref err) => err.description(),
+            Error::Json(ref err) => err.description(),
+            Error::Base64(ref err) => err.description(),
+            Error::JsonDecode(ref err) => err.description(),
+            Error::JsonEncode(ref err) => err.description(),
+            Error::ExpiredSignature => "token has expired",
+            Error::ImmatureSignature => "token is not yet valid",
+            Error::InvalidIssuedAt => "token was issued in the future",
+            Error::InvalidIssuer => "token has an unexpected issuer",
+            Error::InvalidAudience => "token has an unexpected audience",
+            Error::InvalidSubject => "token has an unexpected subject",
+        }
+    }
+}
+
+// Most of the wrapped errors above don't implement `PartialEq`, so this is
+// hand-written rather than derived; it only distinguishes the bare variants
+// (`Format` and the claim-validation ones) callers actually compare against,
+// and treats the wrapped-error variants as never equal to one another.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            (&Error::Format, &Error::Format) => true,
+            (&Error::ExpiredSignature, &Error::ExpiredSignature) => true,
+            (&Error::ImmatureSignature, &Error::ImmatureSignature) => true,
+            (&Error::InvalidIssuedAt, &Error::InvalidIssuedAt) => true,
+            (&Error::InvalidIssuer, &Error::InvalidIssuer) => true,
+            (&Error::InvalidAudience, &Error::InvalidAudience) => true,
+            (&Error::InvalidSubject, &Error::InvalidSubject) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Error {
+        Error::Utf8(err)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(err: ParserError) -> Error {
+        Error::Json(err)
+    }
+}
+
+impl From<FromBase64Error> for Error {
+    fn from(err: FromBase64Error) -> Error {
+        Error::Base64(err)
+    }
+}
+
+impl From<DecoderError> for Error {
+    fn from(err: DecoderError) -> Error {
+        Error::JsonDecode(err)
+    }
+}
+
+impl From<EncoderError> for Error {
+    fn from(err: EncoderError) -> Error {
+        Error::JsonEncode(err)
+    }
+}